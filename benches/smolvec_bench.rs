@@ -4,7 +4,7 @@ pub use smolvec::SmolVec;
 fn bench_push_small(c: &mut Criterion) {
     c.bench_function("push_small_vec", |b| {
         b.iter(|| {
-            let mut vec = SmolVec::new();
+            let mut vec: SmolVec<i32> = SmolVec::new();
             for i in 0..4 {
                 vec.push(black_box(i));
             }
@@ -23,7 +23,7 @@ fn bench_push_small(c: &mut Criterion) {
 fn bench_push_large(c: &mut Criterion) {
     c.bench_function("push_large_small_vec", |b| {
         b.iter(|| {
-            let mut vec = SmolVec::new();
+            let mut vec: SmolVec<i32> = SmolVec::new();
             for i in 0..1000 {
                 vec.push(black_box(i));
             }
@@ -42,7 +42,7 @@ fn bench_push_large(c: &mut Criterion) {
 fn bench_pop(c: &mut Criterion) {
     c.bench_function("pop_small_vec", |b| {
         b.iter(|| {
-            let mut vec = SmolVec::new();
+            let mut vec: SmolVec<i32> = SmolVec::new();
             for i in 0..4 {
                 vec.push(i);
             }
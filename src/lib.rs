@@ -7,46 +7,173 @@ use std::alloc::{self, Layout};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
+#[cfg(feature = "union")]
+use std::mem::ManuallyDrop;
 use std::mem::MaybeUninit;
 use std::ops::{Deref, DerefMut};
+#[cfg(feature = "union")]
+use std::ptr::NonNull;
 use std::ptr;
 
-/// The number of elements that can be stored inline.
-const INLINE_CAPACITY: usize = 16;
+/// The default number of elements that can be stored inline, matching
+/// smallvec's default for `SmallVec<[T; N]>`.
+const DEFAULT_INLINE_CAPACITY: usize = 16;
+
+/// A source of heap memory for a `SmolVec`'s spilled storage.
+///
+/// This mirrors the ABI-safe allocator trait used by crates like
+/// `xlang_abi`, rather than the unstable `std::alloc::Allocator`, so
+/// `SmolVec` keeps building on stable Rust. Implementors hand back raw,
+/// unchecked pointers; callers are responsible for using a `Layout` that
+/// matches the one passed to `deallocate`.
+pub trait Allocator {
+    /// Allocates a block of memory described by `layout`, or returns a null
+    /// pointer if the allocation fails. `SmolVec` checks for null and calls
+    /// [`std::alloc::handle_alloc_error`] on failure, so implementors should
+    /// not abort or panic themselves.
+    fn allocate(&self, layout: Layout) -> *mut u8;
+
+    /// Deallocates a block of memory previously returned by `allocate`
+    /// with the same `layout`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by this allocator's `allocate` with a
+    /// `layout` matching the one given here.
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout);
+}
+
+/// The global heap allocator, and the default `SmolVec` allocator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Global;
+
+impl Allocator for Global {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        unsafe { alloc::alloc(layout) }
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        alloc::dealloc(ptr, layout)
+    }
+}
+
+/// Calls `alloc.allocate(layout)` and aborts via
+/// [`std::alloc::handle_alloc_error`] if it returns a null pointer, rather
+/// than letting a failed allocation silently turn into a null-pointer
+/// dereference later on.
+fn allocate_or_abort<A: Allocator>(alloc: &A, layout: Layout) -> *mut u8 {
+    let ptr = alloc.allocate(layout);
+    if ptr.is_null() {
+        alloc::handle_alloc_error(layout);
+    }
+    ptr
+}
 
 /// A vector implementation with small-vector optimization.
 ///
-/// `SmallVec<T>` stores up to `INLINE_CAPACITY` elements inline,
-/// and only allocates on the heap for larger numbers of elements.
-pub struct SmolVec<T> {
+/// `SmolVec<T, N, A>` stores up to `N` elements inline, and only allocates
+/// from `A` for larger numbers of elements. `N` defaults to 16 and `A`
+/// defaults to the global allocator, but callers can choose a smaller
+/// inline budget for large elements, a larger one for tiny ones, or a
+/// custom allocator for the spilled portion, mirroring
+/// `smallvec::SmallVec<[T; N]>`.
+///
+/// With the `union` feature enabled, `data` is stored in an untagged union
+/// instead of a tagged enum, so `SmolVec<T, N>` can be as small as `Vec<T>`.
+/// In that mode `capacity` doubles as the inline/heap discriminant: a value
+/// `<= N` means the data lives inline, and `> N` means it's on the heap.
+pub struct SmolVec<T, const N: usize = DEFAULT_INLINE_CAPACITY, A: Allocator = Global> {
     len: usize,
-    data: Data<T>,
+    #[cfg(feature = "union")]
+    capacity: usize,
+    data: Data<T, N>,
+    alloc: A,
 }
 
-enum Data<T> {
-    Inline(MaybeUninit<[T; INLINE_CAPACITY]>),
+#[cfg(not(feature = "union"))]
+enum Data<T, const N: usize> {
+    Inline(MaybeUninit<[T; N]>),
     Heap { ptr: *mut T, capacity: usize },
 }
 
-impl<T> SmolVec<T> {
-    /// Creates a new, empty `SmallVec<T>`.
+/// Untagged storage for the `union` feature. Which field is live is
+/// determined entirely by `SmolVec::capacity`, never by a stored tag.
+#[cfg(feature = "union")]
+union Data<T, const N: usize> {
+    inline: ManuallyDrop<MaybeUninit<[T; N]>>,
+    heap: NonNull<T>,
+}
+
+impl<T, const N: usize> SmolVec<T, N, Global> {
+    /// Creates a new, empty `SmolVec<T, N>` backed by the global allocator.
     pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+
+    /// Creates a new `SmolVec<T, N>` with the specified capacity, backed by
+    /// the global allocator.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_in(capacity, Global)
+    }
+}
+
+impl<T, const N: usize, A: Allocator> SmolVec<T, N, A> {
+    /// Creates a new, empty `SmolVec<T, N, A>` using `alloc` for any heap
+    /// spill.
+    #[cfg(not(feature = "union"))]
+    pub fn new_in(alloc: A) -> Self {
         SmolVec {
             len: 0,
             data: Data::Inline(MaybeUninit::uninit()),
+            alloc,
         }
     }
 
-    /// Creates a new `SmallVec<T>` with the specified capacity.
-    pub fn with_capacity(capacity: usize) -> Self {
-        if capacity <= INLINE_CAPACITY {
-            Self::new()
+    /// Creates a new, empty `SmolVec<T, N, A>` using `alloc` for any heap
+    /// spill.
+    #[cfg(feature = "union")]
+    pub fn new_in(alloc: A) -> Self {
+        SmolVec {
+            len: 0,
+            capacity: N,
+            data: Data {
+                inline: ManuallyDrop::new(MaybeUninit::uninit()),
+            },
+            alloc,
+        }
+    }
+
+    /// Creates a new `SmolVec<T, N, A>` with the specified capacity, using
+    /// `alloc` for any heap spill.
+    #[cfg(not(feature = "union"))]
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        if capacity <= N {
+            Self::new_in(alloc)
         } else {
             let layout = Layout::array::<T>(capacity).unwrap();
-            let ptr = unsafe { alloc::alloc(layout) as *mut T };
+            let ptr = allocate_or_abort(&alloc, layout) as *mut T;
             SmolVec {
                 len: 0,
                 data: Data::Heap { ptr, capacity },
+                alloc,
+            }
+        }
+    }
+
+    /// Creates a new `SmolVec<T, N, A>` with the specified capacity, using
+    /// `alloc` for any heap spill.
+    #[cfg(feature = "union")]
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        if capacity <= N {
+            Self::new_in(alloc)
+        } else {
+            let layout = Layout::array::<T>(capacity).unwrap();
+            let ptr = unsafe { NonNull::new_unchecked(allocate_or_abort(&alloc, layout) as *mut T) };
+            SmolVec {
+                len: 0,
+                capacity,
+                data: Data { heap: ptr },
+                alloc,
             }
         }
     }
@@ -54,7 +181,7 @@ impl<T> SmolVec<T> {
     /// Appends an element to the back of the vector.
     pub fn push(&mut self, value: T) {
         if self.len == self.capacity() {
-            self.grow();
+            self.grow_to(self.len + 1);
         }
         unsafe {
             let ptr = self.as_mut_ptr().add(self.len);
@@ -63,6 +190,22 @@ impl<T> SmolVec<T> {
         self.len += 1;
     }
 
+    /// Appends every element of `slice` to the vector via a single bulk
+    /// copy, reserving capacity once up front rather than growing on every
+    /// `push`.
+    pub fn extend_from_slice(&mut self, slice: &[T])
+    where
+        T: Copy,
+    {
+        let new_len = self.len + slice.len();
+        self.grow_to(new_len);
+        unsafe {
+            let dst = self.as_mut_ptr().add(self.len);
+            ptr::copy_nonoverlapping(slice.as_ptr(), dst, slice.len());
+        }
+        self.len = new_len;
+    }
+
     /// Removes the last element from the vector and returns it, or `None` if it is empty.
     pub fn pop(&mut self) -> Option<T> {
         if self.len == 0 {
@@ -80,14 +223,157 @@ impl<T> SmolVec<T> {
         while self.pop().is_some() {}
     }
 
+    /// Inserts an element at position `index`, shifting all elements after
+    /// it to the right.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) {
+        assert!(index <= self.len, "insertion index out of bounds");
+        if self.len == self.capacity() {
+            self.grow_to(self.len + 1);
+        }
+        unsafe {
+            let ptr = self.as_mut_ptr().add(index);
+            if index < self.len {
+                ptr::copy(ptr, ptr.add(1), self.len - index);
+            }
+            ptr::write(ptr, value);
+        }
+        self.len += 1;
+    }
+
+    /// Removes and returns the element at position `index`, shifting all
+    /// elements after it to the left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "removal index out of bounds");
+        unsafe {
+            let ptr = self.as_mut_ptr().add(index);
+            let value = ptr::read(ptr);
+            ptr::copy(ptr.add(1), ptr, self.len - index - 1);
+            self.len -= 1;
+            value
+        }
+    }
+
+    /// Removes and returns the element at position `index`, replacing it
+    /// with the last element instead of shifting the remainder. Faster
+    /// than `remove` when the order of elements doesn't matter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "removal index out of bounds");
+        let last = self.len - 1;
+        unsafe {
+            let base = self.as_mut_ptr();
+            ptr::swap(base.add(index), base.add(last));
+        }
+        self.pop().unwrap()
+    }
+
+    /// Shortens the vector, keeping the first `len` elements and dropping
+    /// the rest. Does nothing if `len` is greater than or equal to the
+    /// vector's current length.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        unsafe {
+            let remaining = ptr::slice_from_raw_parts_mut(self.as_mut_ptr().add(len), self.len - len);
+            // Shrink `len` before dropping so a panicking destructor
+            // doesn't leave us pointing at elements we've already dropped.
+            self.len = len;
+            ptr::drop_in_place(remaining);
+        }
+    }
+
+    /// Retains only the elements for which `f` returns `true`, dropping the
+    /// rest and preserving the relative order of the ones that remain.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let len = self.len;
+        let mut removed = 0;
+        {
+            let slice = &mut self[..];
+            for i in 0..len {
+                if !f(&slice[i]) {
+                    removed += 1;
+                } else if removed > 0 {
+                    slice.swap(i - removed, i);
+                }
+            }
+        }
+        if removed > 0 {
+            self.truncate(len - removed);
+        }
+    }
+
+    /// Removes the elements in `range` and returns an iterator over the
+    /// removed elements.
+    ///
+    /// If the returned iterator is dropped before being fully consumed, any
+    /// remaining elements in the range are dropped and the tail is
+    /// compacted, exactly as if it had been consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range's start is greater than its end, or if the
+    /// range's end is out of bounds.
+    pub fn drain<R>(&mut self, range: R) -> Drain<'_, T, N, A>
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        let len = self.len;
+        let start = match range.start_bound() {
+            std::ops::Bound::Included(&n) => n,
+            std::ops::Bound::Excluded(&n) => n + 1,
+            std::ops::Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            std::ops::Bound::Included(&n) => n + 1,
+            std::ops::Bound::Excluded(&n) => n,
+            std::ops::Bound::Unbounded => len,
+        };
+        assert!(start <= end && end <= len, "drain range out of bounds");
+
+        // Shrink `len` to `start` up front so a leaked `Drain` (e.g. via
+        // `mem::forget`) simply leaks the un-compacted tail instead of
+        // exposing or double-dropping it.
+        self.len = start;
+
+        let drain_ptr = unsafe { self.as_ptr().add(start) };
+        Drain {
+            drain_iter: unsafe { std::slice::from_raw_parts(drain_ptr, end - start).iter() },
+            vec: self,
+            tail_start: end,
+            tail_len: len - end,
+        }
+    }
+
     /// Returns the number of elements the vector can hold without reallocating.
+    #[cfg(not(feature = "union"))]
     pub fn capacity(&self) -> usize {
         match &self.data {
-            Data::Inline(_) => INLINE_CAPACITY,
+            Data::Inline(_) => N,
             Data::Heap { capacity, .. } => *capacity,
         }
     }
 
+    /// Returns the number of elements the vector can hold without reallocating.
+    #[cfg(feature = "union")]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
     /// Returns the number of elements in the vector.
     pub fn len(&self) -> usize {
         self.len
@@ -98,15 +384,113 @@ impl<T> SmolVec<T> {
         self.len == 0
     }
 
-    fn grow(&mut self) {
-        let new_capacity = if self.capacity() == 0 {
-            INLINE_CAPACITY
-        } else {
-            self.capacity() * 2
+    /// Returns `true` if the vector has spilled its elements onto the heap.
+    #[cfg(not(feature = "union"))]
+    pub fn spilled(&self) -> bool {
+        matches!(self.data, Data::Heap { .. })
+    }
+
+    /// Returns `true` if the vector has spilled its elements onto the heap.
+    #[cfg(feature = "union")]
+    pub fn spilled(&self) -> bool {
+        self.is_heap()
+    }
+
+    /// Moves the elements back into inline storage and frees the heap
+    /// buffer if the vector has spilled but no longer needs to (`len <=
+    /// N`); otherwise, if still spilled, reallocates the heap buffer down
+    /// to exactly `len`. Does nothing if the vector is already inline.
+    #[cfg(not(feature = "union"))]
+    pub fn shrink_to_fit(&mut self) {
+        let (ptr, capacity) = match self.data {
+            Data::Heap { ptr, capacity } => (ptr, capacity),
+            Data::Inline(_) => return,
         };
 
+        if self.len <= N {
+            let mut inline = MaybeUninit::<[T; N]>::uninit();
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, inline.as_mut_ptr() as *mut T, self.len);
+            }
+            let layout = Layout::array::<T>(capacity).unwrap();
+            unsafe { self.alloc.deallocate(ptr as *mut u8, layout) };
+            self.data = Data::Inline(inline);
+        } else if self.len < capacity {
+            let new_layout = Layout::array::<T>(self.len).unwrap();
+            let new_ptr = allocate_or_abort(&self.alloc, new_layout) as *mut T;
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, self.len);
+            }
+            let old_layout = Layout::array::<T>(capacity).unwrap();
+            unsafe { self.alloc.deallocate(ptr as *mut u8, old_layout) };
+            self.data = Data::Heap {
+                ptr: new_ptr,
+                capacity: self.len,
+            };
+        }
+    }
+
+    /// Moves the elements back into inline storage and frees the heap
+    /// buffer if the vector has spilled but no longer needs to (`len <=
+    /// N`); otherwise, if still spilled, reallocates the heap buffer down
+    /// to exactly `len`. Does nothing if the vector is already inline.
+    #[cfg(feature = "union")]
+    pub fn shrink_to_fit(&mut self) {
+        if !self.is_heap() {
+            return;
+        }
+        let (ptr, capacity) = unsafe { (self.data.heap, self.capacity) };
+
+        if self.len <= N {
+            let mut inline = ManuallyDrop::new(MaybeUninit::<[T; N]>::uninit());
+            unsafe {
+                ptr::copy_nonoverlapping(ptr.as_ptr(), inline.as_mut_ptr() as *mut T, self.len);
+            }
+            let layout = Layout::array::<T>(capacity).unwrap();
+            unsafe { self.alloc.deallocate(ptr.as_ptr() as *mut u8, layout) };
+            self.data = Data { inline };
+            self.capacity = N;
+        } else if self.len < capacity {
+            let new_layout = Layout::array::<T>(self.len).unwrap();
+            let new_ptr = unsafe { NonNull::new_unchecked(allocate_or_abort(&self.alloc, new_layout) as *mut T) };
+            unsafe {
+                ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), self.len);
+            }
+            let old_layout = Layout::array::<T>(capacity).unwrap();
+            unsafe { self.alloc.deallocate(ptr.as_ptr() as *mut u8, old_layout) };
+            self.data = Data { heap: new_ptr };
+            self.capacity = self.len;
+        }
+    }
+
+    /// Returns `true` if the data has spilled onto the heap.
+    #[cfg(feature = "union")]
+    fn is_heap(&self) -> bool {
+        self.capacity > N
+    }
+
+    #[cfg(all(test, not(feature = "union")))]
+    fn is_inline(&self) -> bool {
+        matches!(self.data, Data::Inline(_))
+    }
+
+    #[cfg(all(test, feature = "union"))]
+    fn is_inline(&self) -> bool {
+        !self.is_heap()
+    }
+
+    /// Grows the backing storage so that `capacity() >= min_capacity`,
+    /// reallocating onto the heap exactly once. Does nothing if already
+    /// large enough.
+    #[cfg(not(feature = "union"))]
+    fn grow_to(&mut self, min_capacity: usize) {
+        if min_capacity <= self.capacity() {
+            return;
+        }
+        let new_capacity = min_capacity.max(self.capacity() * 2);
+
         let new_layout = Layout::array::<T>(new_capacity).unwrap();
-        let new_ptr = unsafe { alloc::alloc(new_layout) as *mut T };
+        let new_ptr = allocate_or_abort(&self.alloc, new_layout) as *mut T;
 
         let old_ptr = self.as_ptr();
         unsafe {
@@ -114,10 +498,8 @@ impl<T> SmolVec<T> {
         }
 
         if let Data::Heap { ptr, capacity } = self.data {
-            unsafe {
-                let old_layout = Layout::array::<T>(capacity).unwrap();
-                alloc::dealloc(ptr as *mut u8, old_layout);
-            }
+            let old_layout = Layout::array::<T>(capacity).unwrap();
+            unsafe { self.alloc.deallocate(ptr as *mut u8, old_layout) };
         }
 
         self.data = Data::Heap {
@@ -126,6 +508,37 @@ impl<T> SmolVec<T> {
         };
     }
 
+    /// Grows the backing storage so that `capacity() >= min_capacity`,
+    /// reallocating onto the heap exactly once. Does nothing if already
+    /// large enough.
+    #[cfg(feature = "union")]
+    fn grow_to(&mut self, min_capacity: usize) {
+        if min_capacity <= self.capacity {
+            return;
+        }
+        let new_capacity = min_capacity.max(self.capacity * 2);
+
+        let new_layout = Layout::array::<T>(new_capacity).unwrap();
+        let new_ptr = unsafe { NonNull::new_unchecked(allocate_or_abort(&self.alloc, new_layout) as *mut T) };
+
+        let old_ptr = self.as_ptr();
+        unsafe {
+            ptr::copy_nonoverlapping(old_ptr, new_ptr.as_ptr(), self.len);
+        }
+
+        if self.is_heap() {
+            let old_layout = Layout::array::<T>(self.capacity).unwrap();
+            unsafe {
+                self.alloc
+                    .deallocate(self.data.heap.as_ptr() as *mut u8, old_layout)
+            };
+        }
+
+        self.data = Data { heap: new_ptr };
+        self.capacity = new_capacity;
+    }
+
+    #[cfg(not(feature = "union"))]
     fn as_ptr(&self) -> *const T {
         match &self.data {
             Data::Inline(ref arr) => arr.as_ptr() as *const T,
@@ -133,6 +546,18 @@ impl<T> SmolVec<T> {
         }
     }
 
+    #[cfg(feature = "union")]
+    fn as_ptr(&self) -> *const T {
+        unsafe {
+            if self.is_heap() {
+                self.data.heap.as_ptr() as *const T
+            } else {
+                self.data.inline.as_ptr() as *const T
+            }
+        }
+    }
+
+    #[cfg(not(feature = "union"))]
     fn as_mut_ptr(&mut self) -> *mut T {
         match self.data {
             Data::Inline(ref mut arr) => arr.as_mut_ptr() as *mut T,
@@ -140,6 +565,18 @@ impl<T> SmolVec<T> {
         }
     }
 
+    #[cfg(feature = "union")]
+    fn as_mut_ptr(&mut self) -> *mut T {
+        unsafe {
+            if self.is_heap() {
+                self.data.heap.as_ptr()
+            } else {
+                (*self.data.inline).as_mut_ptr() as *mut T
+            }
+        }
+    }
+
+    #[cfg(not(feature = "union"))]
     fn into_vec(self) -> Vec<T> {
         let mut vec = Vec::with_capacity(self.len);
         for i in 0..self.len {
@@ -151,17 +588,37 @@ impl<T> SmolVec<T> {
 
         // If SmolVec was using heap, deallocate the heap memory
         if let Data::Heap { ptr, capacity } = self.data {
+            let layout = Layout::array::<T>(capacity).unwrap();
+            unsafe { self.alloc.deallocate(ptr as *mut u8, layout) };
+        }
+
+        vec
+    }
+
+    #[cfg(feature = "union")]
+    fn into_vec(self) -> Vec<T> {
+        let mut vec = Vec::with_capacity(self.len);
+        for i in 0..self.len {
+            // We need to move the elements out of SmolVec into Vec
             unsafe {
-                let layout = Layout::array::<T>(capacity).unwrap();
-                alloc::dealloc(ptr as *mut u8, layout);
+                vec.push(ptr::read(self.as_ptr().add(i)));
             }
         }
 
+        // If SmolVec was using heap, deallocate the heap memory
+        if self.is_heap() {
+            let layout = Layout::array::<T>(self.capacity).unwrap();
+            unsafe {
+                self.alloc
+                    .deallocate(self.data.heap.as_ptr() as *mut u8, layout)
+            };
+        }
+
         vec
     }
 }
 
-impl<T> Deref for SmolVec<T> {
+impl<T, const N: usize, A: Allocator> Deref for SmolVec<T, N, A> {
     type Target = [T];
 
     fn deref(&self) -> &[T] {
@@ -169,47 +626,62 @@ impl<T> Deref for SmolVec<T> {
     }
 }
 
-impl<T> DerefMut for SmolVec<T> {
+impl<T, const N: usize, A: Allocator> DerefMut for SmolVec<T, N, A> {
     fn deref_mut(&mut self) -> &mut [T] {
         unsafe { std::slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
     }
 }
 
-impl<T> Drop for SmolVec<T> {
+#[cfg(not(feature = "union"))]
+impl<T, const N: usize, A: Allocator> Drop for SmolVec<T, N, A> {
     fn drop(&mut self) {
         unsafe {
             ptr::drop_in_place(&mut self[..]);
         }
         if let Data::Heap { ptr, capacity } = self.data {
+            let layout = Layout::array::<T>(capacity).unwrap();
+            unsafe { self.alloc.deallocate(ptr as *mut u8, layout) };
+        }
+    }
+}
+
+#[cfg(feature = "union")]
+impl<T, const N: usize, A: Allocator> Drop for SmolVec<T, N, A> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(&mut self[..]);
+        }
+        if self.is_heap() {
+            let layout = Layout::array::<T>(self.capacity).unwrap();
             unsafe {
-                let layout = Layout::array::<T>(capacity).unwrap();
-                alloc::dealloc(ptr as *mut u8, layout);
-            }
+                self.alloc
+                    .deallocate(self.data.heap.as_ptr() as *mut u8, layout)
+            };
         }
     }
 }
 
-impl<T: Clone> Clone for SmolVec<T> {
+impl<T: Clone, const N: usize, A: Allocator + Clone> Clone for SmolVec<T, N, A> {
     fn clone(&self) -> Self {
-        let mut new_vec = SmolVec::with_capacity(self.len);
+        let mut new_vec = SmolVec::with_capacity_in(self.len, self.alloc.clone());
         new_vec.extend(self.iter().cloned());
         new_vec
     }
 }
 
-impl<T: fmt::Debug> fmt::Debug for SmolVec<T> {
+impl<T: fmt::Debug, const N: usize, A: Allocator> fmt::Debug for SmolVec<T, N, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_list().entries(self.iter()).finish()
     }
 }
 
-impl<T: PartialEq> PartialEq for SmolVec<T> {
+impl<T: PartialEq, const N: usize, A: Allocator> PartialEq for SmolVec<T, N, A> {
     fn eq(&self, other: &Self) -> bool {
         self.len() == other.len() && self.iter().eq(other.iter())
     }
 }
 
-impl<T> IntoIterator for SmolVec<T> {
+impl<T, const N: usize, A: Allocator> IntoIterator for SmolVec<T, N, A> {
     type Item = T;
     type IntoIter = std::vec::IntoIter<T>;
 
@@ -218,7 +690,7 @@ impl<T> IntoIterator for SmolVec<T> {
     }
 }
 
-impl<'a, T> IntoIterator for &'a SmolVec<T> {
+impl<'a, T, const N: usize, A: Allocator> IntoIterator for &'a SmolVec<T, N, A> {
     type Item = &'a T;
     type IntoIter = std::slice::Iter<'a, T>;
 
@@ -227,7 +699,7 @@ impl<'a, T> IntoIterator for &'a SmolVec<T> {
     }
 }
 
-impl<'a, T> IntoIterator for &'a mut SmolVec<T> {
+impl<'a, T, const N: usize, A: Allocator> IntoIterator for &'a mut SmolVec<T, N, A> {
     type Item = &'a mut T;
     type IntoIter = std::slice::IterMut<'a, T>;
 
@@ -236,21 +708,68 @@ impl<'a, T> IntoIterator for &'a mut SmolVec<T> {
     }
 }
 
-impl<T: Eq> Eq for SmolVec<T> {}
+/// An iterator that removes and yields a range of elements from a
+/// `SmolVec`, produced by [`SmolVec::drain`].
+///
+/// When dropped, any elements not yet yielded are dropped and the
+/// remaining tail of the vector is shifted back to close the gap.
+pub struct Drain<'a, T, const N: usize, A: Allocator> {
+    vec: &'a mut SmolVec<T, N, A>,
+    drain_iter: std::slice::Iter<'a, T>,
+    tail_start: usize,
+    tail_len: usize,
+}
+
+impl<'a, T, const N: usize, A: Allocator> Iterator for Drain<'a, T, N, A> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.drain_iter
+            .next()
+            .map(|elem| unsafe { ptr::read(elem) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.drain_iter.size_hint()
+    }
+}
+
+impl<'a, T, const N: usize, A: Allocator> Drop for Drain<'a, T, N, A> {
+    fn drop(&mut self) {
+        // Drop whatever the caller didn't consume.
+        for elem in self.drain_iter.by_ref() {
+            unsafe {
+                ptr::drop_in_place(elem as *const T as *mut T);
+            }
+        }
+        // Shift the tail back to close the gap, then restore `len`.
+        if self.tail_len > 0 {
+            unsafe {
+                let start = self.vec.len;
+                let src = self.vec.as_ptr().add(self.tail_start);
+                let dst = self.vec.as_mut_ptr().add(start);
+                ptr::copy(src, dst, self.tail_len);
+            }
+        }
+        self.vec.len += self.tail_len;
+    }
+}
+
+impl<T: Eq, const N: usize, A: Allocator> Eq for SmolVec<T, N, A> {}
 
-impl<T: PartialOrd> PartialOrd for SmolVec<T> {
+impl<T: PartialOrd, const N: usize, A: Allocator> PartialOrd for SmolVec<T, N, A> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.iter().partial_cmp(other.iter())
     }
 }
 
-impl<T: Ord> Ord for SmolVec<T> {
+impl<T: Ord, const N: usize, A: Allocator> Ord for SmolVec<T, N, A> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         self.iter().cmp(other.iter())
     }
 }
 
-impl<T: Hash> Hash for SmolVec<T> {
+impl<T: Hash, const N: usize, A: Allocator> Hash for SmolVec<T, N, A> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.len().hash(state);
         for item in self {
@@ -259,13 +778,13 @@ impl<T: Hash> Hash for SmolVec<T> {
     }
 }
 
-impl<T> Default for SmolVec<T> {
+impl<T, const N: usize> Default for SmolVec<T, N, Global> {
     fn default() -> Self {
         SmolVec::new()
     }
 }
 
-impl<T> Extend<T> for SmolVec<T> {
+impl<T, const N: usize, A: Allocator> Extend<T> for SmolVec<T, N, A> {
     fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
         for item in iter {
             self.push(item);
@@ -273,7 +792,7 @@ impl<T> Extend<T> for SmolVec<T> {
     }
 }
 
-impl<T> FromIterator<T> for SmolVec<T> {
+impl<T, const N: usize> FromIterator<T> for SmolVec<T, N, Global> {
     fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
         let mut vec = SmolVec::new();
         vec.extend(iter);
@@ -281,13 +800,106 @@ impl<T> FromIterator<T> for SmolVec<T> {
     }
 }
 
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize, const N: usize, A: Allocator> serde::Serialize for SmolVec<T, N, A> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// Caps a deserializer-supplied `size_hint` before using it to preallocate,
+/// mirroring `serde`'s own `size_hint::cautious` helper. `size_hint` comes
+/// from the input data (e.g. a length prefix in a binary format), so trusting
+/// it directly would let a malicious payload force an enormous allocation
+/// before a single element is actually read.
+#[cfg(feature = "serde")]
+fn cautious_capacity<T>(hint: Option<usize>) -> usize {
+    const MAX_PREALLOC_BYTES: usize = 1024 * 1024;
+
+    let element_size = std::mem::size_of::<T>();
+    if element_size == 0 {
+        0
+    } else {
+        hint.unwrap_or(0)
+            .min(MAX_PREALLOC_BYTES.checked_div(element_size).unwrap_or(usize::MAX))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T, const N: usize, A> serde::Deserialize<'de> for SmolVec<T, N, A>
+where
+    T: serde::Deserialize<'de>,
+    A: Allocator + Default,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct SmolVecVisitor<T, const N: usize, A: Allocator> {
+            marker: std::marker::PhantomData<(T, A)>,
+        }
+
+        impl<'de, T, const N: usize, A> serde::de::Visitor<'de> for SmolVecVisitor<T, N, A>
+        where
+            T: serde::Deserialize<'de>,
+            A: Allocator + Default,
+        {
+            type Value = SmolVec<T, N, A>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("a sequence")
+            }
+
+            fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
+            where
+                S: serde::de::SeqAccess<'de>,
+            {
+                // Pre-size using the sequence's hint so large sequences
+                // don't repeatedly `grow` while deserializing, but cap it
+                // first so an untrusted, attacker-controlled hint can't
+                // force a huge up-front allocation.
+                let capacity = cautious_capacity::<T>(seq.size_hint());
+                let mut vec = SmolVec::with_capacity_in(capacity, A::default());
+                while let Some(value) = seq.next_element()? {
+                    vec.push(value);
+                }
+                Ok(vec)
+            }
+        }
+
+        deserializer.deserialize_seq(SmolVecVisitor {
+            marker: std::marker::PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "write")]
+impl<const N: usize, A: Allocator> std::io::Write for SmolVec<u8, N, A> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_push_pop() {
-        let mut vec = SmolVec::new();
+        let mut vec: SmolVec<_> = SmolVec::new();
         for i in 0..20 {
             vec.push(i);
         }
@@ -300,18 +912,49 @@ mod tests {
 
     #[test]
     fn test_inline_to_heap() {
-        let mut vec = SmolVec::new();
-        for i in 0..INLINE_CAPACITY {
+        let mut vec: SmolVec<_> = SmolVec::new();
+        for i in 0..DEFAULT_INLINE_CAPACITY {
             vec.push(i);
         }
-        assert!(matches!(vec.data, Data::Inline(_)));
-        vec.push(INLINE_CAPACITY);
-        assert!(matches!(vec.data, Data::Heap { .. }));
+        assert!(vec.is_inline());
+        vec.push(DEFAULT_INLINE_CAPACITY);
+        assert!(!vec.is_inline());
+    }
+
+    #[test]
+    fn test_custom_inline_capacity() {
+        let mut vec: SmolVec<_, 2> = SmolVec::new();
+        vec.push(1);
+        vec.push(2);
+        assert_eq!(vec.capacity(), 2);
+        assert!(vec.is_inline());
+        vec.push(3);
+        assert!(!vec.is_inline());
+        assert_eq!(&vec[..], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_zero_inline_capacity() {
+        let mut vec: SmolVec<_, 0> = SmolVec::new();
+        assert_eq!(vec.capacity(), 0);
+        for i in 0..6 {
+            vec.push(i);
+        }
+        assert_eq!(&vec[..], &[0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    #[cfg(feature = "union")]
+    fn test_union_matches_vec_size() {
+        assert_eq!(
+            std::mem::size_of::<SmolVec<u64, 0>>(),
+            std::mem::size_of::<Vec<u64>>()
+        );
     }
 
     #[test]
     fn test_clone() {
-        let mut vec = SmolVec::new();
+        let mut vec: SmolVec<_> = SmolVec::new();
         vec.extend(0..10);
         let clone = vec.clone();
         assert_eq!(vec, clone);
@@ -319,8 +962,8 @@ mod tests {
 
     #[test]
     fn test_eq() {
-        let mut vec1 = SmolVec::new();
-        let mut vec2 = SmolVec::new();
+        let mut vec1: SmolVec<_> = SmolVec::new();
+        let mut vec2: SmolVec<_> = SmolVec::new();
         vec1.extend(0..5);
         vec2.extend(0..5);
         assert_eq!(vec1, vec2);
@@ -330,8 +973,8 @@ mod tests {
 
     #[test]
     fn test_ord() {
-        let mut vec1 = SmolVec::new();
-        let mut vec2 = SmolVec::new();
+        let mut vec1: SmolVec<_> = SmolVec::new();
+        let mut vec2: SmolVec<_> = SmolVec::new();
         vec1.extend(0..5);
         vec2.extend(0..6);
         assert!(vec1 < vec2);
@@ -343,4 +986,133 @@ mod tests {
         assert_eq!(vec.len(), 10);
         assert_eq!(vec[5], 5);
     }
+
+    #[derive(Clone, Copy, Default)]
+    struct ForwardingAllocator;
+
+    impl Allocator for ForwardingAllocator {
+        fn allocate(&self, layout: Layout) -> *mut u8 {
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    #[test]
+    fn test_custom_allocator() {
+        let mut vec: SmolVec<_, 2, _> = SmolVec::new_in(ForwardingAllocator);
+        vec.extend(0..10);
+        assert_eq!(vec.len(), 10);
+        assert_eq!(&vec[..], &(0..10).collect::<Vec<_>>()[..]);
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut vec: SmolVec<_> = (0..5).collect();
+        vec.insert(2, 99);
+        assert_eq!(&vec[..], &[0, 1, 99, 2, 3, 4]);
+        assert_eq!(vec.remove(2), 99);
+        assert_eq!(&vec[..], &[0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_swap_remove() {
+        let mut vec: SmolVec<_> = (0..5).collect();
+        assert_eq!(vec.swap_remove(1), 1);
+        assert_eq!(&vec[..], &[0, 4, 2, 3]);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut vec: SmolVec<_> = (0..5).collect();
+        vec.truncate(3);
+        assert_eq!(&vec[..], &[0, 1, 2]);
+        vec.truncate(10);
+        assert_eq!(&vec[..], &[0, 1, 2]);
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut vec: SmolVec<_> = (0..10).collect();
+        vec.retain(|&x| x % 2 == 0);
+        assert_eq!(&vec[..], &[0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut vec: SmolVec<_> = (0..5).collect();
+        let drained: Vec<_> = vec.drain(1..3).collect();
+        assert_eq!(drained, vec![1, 2]);
+        assert_eq!(&vec[..], &[0, 3, 4]);
+    }
+
+    #[test]
+    fn test_drain_dropped_without_consuming() {
+        let mut vec: SmolVec<_> = (0..5).collect();
+        vec.drain(1..3);
+        assert_eq!(&vec[..], &[0, 3, 4]);
+    }
+
+    #[test]
+    fn test_spilled() {
+        let mut vec: SmolVec<_, 2> = SmolVec::new();
+        assert!(!vec.spilled());
+        vec.extend(0..3);
+        assert!(vec.spilled());
+    }
+
+    #[test]
+    fn test_shrink_to_fit_returns_inline() {
+        let mut vec: SmolVec<_, 2> = SmolVec::new();
+        vec.extend(0..5);
+        assert!(vec.spilled());
+        vec.truncate(2);
+        vec.shrink_to_fit();
+        assert!(!vec.spilled());
+        assert_eq!(&vec[..], &[0, 1]);
+    }
+
+    #[test]
+    fn test_shrink_to_fit_reallocates_down() {
+        let mut vec: SmolVec<_, 2> = SmolVec::new();
+        vec.extend(0..10);
+        let spilled_capacity = vec.capacity();
+        vec.truncate(5);
+        vec.shrink_to_fit();
+        assert!(vec.spilled());
+        assert!(vec.capacity() < spilled_capacity);
+        assert_eq!(vec.capacity(), 5);
+        assert_eq!(&vec[..], &[0, 1, 2, 3, 4]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut vec: SmolVec<_> = SmolVec::new();
+        vec.extend(0..20);
+        let json = serde_json::to_string(&vec).unwrap();
+        let round_tripped: SmolVec<i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(vec, round_tripped);
+    }
+
+    #[test]
+    fn test_extend_from_slice() {
+        let mut vec: SmolVec<_, 2> = SmolVec::new();
+        vec.push(0);
+        vec.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(&vec[..], &[0, 1, 2, 3]);
+    }
+
+    #[cfg(feature = "write")]
+    #[test]
+    fn test_io_write() {
+        use std::io::Write;
+
+        let mut vec: SmolVec<u8, 4> = SmolVec::new();
+        vec.write_all(b"hello world").unwrap();
+        vec.flush().unwrap();
+        assert_eq!(&vec[..], b"hello world");
+    }
 }